@@ -0,0 +1,171 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A compressor registry decoupled from [`rocksdb::DBCompressionType`].
+//!
+//! `CompressionType` is a closed mapping onto RocksDB's enum, so TiKV-side layers that do their
+//! own compression (snapshots, backups, scatter exports) can't reach a codec RocksDB doesn't
+//! expose. A [`Compressor`] is instead identified by a stable one-byte id that gets persisted
+//! alongside the data it produced, so anything written by one codec can always be decoded
+//! later, whether or not the process that reads it still has the writer's config around.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+
+use crate::config::CompressionType;
+
+pub type Result<T> = io::Result<T>;
+
+/// A self-describing compression codec. `ID` is persisted in framed output (see
+/// `parallel_compress`) so data can be decoded without knowing which `Compressor` produced it
+/// ahead of time — only that it's registered.
+pub trait Compressor: Send + Sync {
+    const ID: u8
+    where
+        Self: Sized;
+
+    fn id(&self) -> u8;
+    fn encode(&self, data: &[u8]) -> Vec<u8>;
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+struct NoneCompressor;
+impl Compressor for NoneCompressor {
+    const ID: u8 = 0;
+    fn id(&self) -> u8 {
+        Self::ID
+    }
+    fn encode(&self, data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+}
+
+struct SnappyCompressor;
+impl Compressor for SnappyCompressor {
+    const ID: u8 = 1;
+    fn id(&self) -> u8 {
+        Self::ID
+    }
+    fn encode(&self, data: &[u8]) -> Vec<u8> {
+        snap::raw::Encoder::new()
+            .compress_vec(data)
+            .expect("snappy compression never fails on well-formed input")
+    }
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        snap::raw::Decoder::new()
+            .decompress_vec(data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+struct Lz4Compressor;
+impl Compressor for Lz4Compressor {
+    const ID: u8 = 4;
+    fn id(&self) -> u8 {
+        Self::ID
+    }
+    fn encode(&self, data: &[u8]) -> Vec<u8> {
+        lz4::block::compress(data, None, false).expect("lz4 compression failed")
+    }
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        lz4::block::decompress(data, None).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+struct ZstdCompressor;
+impl Compressor for ZstdCompressor {
+    const ID: u8 = 6;
+    fn id(&self) -> u8 {
+        Self::ID
+    }
+    fn encode(&self, data: &[u8]) -> Vec<u8> {
+        zstd::stream::encode_all(data, 0).expect("zstd compression failed")
+    }
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        zstd::stream::decode_all(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<HashMap<u8, Arc<dyn Compressor>>> = {
+        let mut m: HashMap<u8, Arc<dyn Compressor>> = HashMap::new();
+        m.insert(NoneCompressor::ID, Arc::new(NoneCompressor));
+        m.insert(SnappyCompressor::ID, Arc::new(SnappyCompressor));
+        m.insert(Lz4Compressor::ID, Arc::new(Lz4Compressor));
+        m.insert(ZstdCompressor::ID, Arc::new(ZstdCompressor));
+        Mutex::new(m)
+    };
+}
+
+/// Registers a custom codec under its own id, so user code can introduce a compressor RocksDB
+/// itself doesn't know about without touching the RocksDB FFI enum. Panics if `id` is already
+/// registered, mirroring the closed-mapping invariant the built-ins rely on.
+pub fn register_compressor(compressor: Arc<dyn Compressor>) {
+    let mut registry = REGISTRY.lock().unwrap();
+    let id = compressor.id();
+    if registry.contains_key(&id) {
+        // Release the lock before panicking so the registry isn't poisoned for other callers.
+        drop(registry);
+        panic!("compressor id {} is already registered", id);
+    }
+    registry.insert(id, compressor);
+}
+
+/// Looks up a previously registered compressor by the id persisted alongside its output.
+pub fn get_compressor(id: u8) -> Option<Arc<dyn Compressor>> {
+    REGISTRY.lock().unwrap().get(&id).cloned()
+}
+
+/// Resolves the config-facing `CompressionType` to the built-in `Compressor` that implements
+/// it. `Zlib`/`Bz2` have no standalone implementation here (they're only meaningful through
+/// RocksDB's own compression, not this byte-oriented registry) and fall back to `no`.
+pub fn resolve(tp: CompressionType) -> Arc<dyn Compressor> {
+    let id = match tp {
+        CompressionType::No | CompressionType::Zlib | CompressionType::Bz2 => NoneCompressor::ID,
+        CompressionType::Snappy => SnappyCompressor::ID,
+        CompressionType::Lz4 | CompressionType::Lz4hc => Lz4Compressor::ID,
+        CompressionType::Zstd | CompressionType::ZstdNotFinal => ZstdCompressor::ID,
+    };
+    get_compressor(id).expect("built-in compressors are always registered")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_roundtrip() {
+        let data = b"the quick brown fox jumps over the lazy dog".repeat(100);
+        for tp in [
+            CompressionType::No,
+            CompressionType::Snappy,
+            CompressionType::Lz4,
+            CompressionType::Zstd,
+        ] {
+            let compressor = resolve(tp);
+            let encoded = compressor.encode(&data);
+            let decoded = compressor.decode(&encoded).unwrap();
+            assert_eq!(decoded, data, "roundtrip mismatch for {:?}", tp);
+        }
+    }
+
+    #[test]
+    fn test_compressor_is_self_describing() {
+        let compressor = resolve(CompressionType::Zstd);
+        let encoded = compressor.encode(b"payload");
+        // A reader only needs the id, not the original `CompressionType`, to decode.
+        let by_id = get_compressor(compressor.id()).unwrap();
+        assert_eq!(by_id.decode(&encoded).unwrap(), b"payload");
+    }
+
+    #[test]
+    #[should_panic(expected = "already registered")]
+    fn test_register_duplicate_id_panics() {
+        register_compressor(Arc::new(NoneCompressor));
+    }
+}
@@ -2,9 +2,10 @@
 
 use online_config::ConfigValue;
 pub use rocksdb::PerfLevel;
-use rocksdb::{CompactionPriority, DBCompactionStyle, DBCompressionType, DBInfoLogLevel, DBRateLimiterMode, DBRecoveryMode, DBTitanDBBlobRunMode};
+use rocksdb::{CompactionPriority, ColumnFamilyOptions, CompressionOptions, DBCompactionStyle, DBCompressionType, DBInfoLogLevel, DBOptions, DBRateLimiterMode, DBRecoveryMode, DBTitanDBBlobRunMode};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
+use tikv_util::config::ReadableSize;
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -31,14 +32,32 @@ impl From<LogLevel> for DBInfoLogLevel {
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum CompressionType {
-    No,
-    Snappy,
-    Zlib,
-    Bz2,
-    Lz4,
-    Lz4hc,
-    Zstd,
-    ZstdNotFinal,
+    No = 0,
+    Snappy = 1,
+    Zlib = 2,
+    Bz2 = 3,
+    Lz4 = 4,
+    Lz4hc = 5,
+    Zstd = 6,
+    ZstdNotFinal = 7,
+}
+
+impl CompressionType {
+    /// Recovers a `CompressionType` from the one-byte tag produced by casting a `CompressionType`
+    /// as `u8` (its discriminant).
+    pub fn from_u8(tag: u8) -> Option<CompressionType> {
+        match tag {
+            0 => Some(CompressionType::No),
+            1 => Some(CompressionType::Snappy),
+            2 => Some(CompressionType::Zlib),
+            3 => Some(CompressionType::Bz2),
+            4 => Some(CompressionType::Lz4),
+            5 => Some(CompressionType::Lz4hc),
+            6 => Some(CompressionType::Zstd),
+            7 => Some(CompressionType::ZstdNotFinal),
+            _ => None,
+        }
+    }
 }
 
 impl From<CompressionType> for DBCompressionType {
@@ -56,6 +75,41 @@ impl From<CompressionType> for DBCompressionType {
     }
 }
 
+/// Below this many bytes, compressing a block costs more CPU than it saves in space, so the
+/// write path should skip it regardless of the configured codec.
+pub const DEFAULT_COMPRESSION_THRESHOLD: ReadableSize = ReadableSize(256);
+
+/// Configuration for skipping compression on small blocks/values.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct CompressionConfig {
+    pub compression_threshold: ReadableSize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> CompressionConfig {
+        CompressionConfig {
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Returns the codec that should actually be used for a buffer of `len` bytes: `No` if it
+    /// is smaller than `compression_threshold`, otherwise the caller-supplied `tp` unchanged.
+    pub fn effective_compression_type(&self, len: usize, tp: DBCompressionType) -> DBCompressionType {
+        if (len as u64) < self.compression_threshold.0 {
+            DBCompressionType::No
+        } else {
+            tp
+        }
+    }
+}
+
+/// Serde support for a per-level compression configuration that additionally carries an
+/// optional compression level for codecs that support one, e.g. `"zstd:6"` or `"zstd(level=6)"`.
+/// Entries without a level (e.g. `"zstd"`, `"no"`) keep their previous meaning.
 pub mod compression_type_level_serde {
     use std::fmt;
 
@@ -65,45 +119,111 @@ pub mod compression_type_level_serde {
 
     use rocksdb::DBCompressionType;
 
-    pub fn serialize<S>(ts: &[DBCompressionType; 7], serializer: S) -> Result<S::Ok, S::Error>
+    /// Whether `t` accepts a compression level. RocksDB ignores levels for `no` and `snappy`.
+    fn accepts_level(t: DBCompressionType) -> bool {
+        !matches!(t, DBCompressionType::No | DBCompressionType::Snappy)
+    }
+
+    fn name_of(t: DBCompressionType) -> &'static str {
+        match t {
+            DBCompressionType::No => "no",
+            DBCompressionType::Snappy => "snappy",
+            DBCompressionType::Zlib => "zlib",
+            DBCompressionType::Bz2 => "bzip2",
+            DBCompressionType::Lz4 => "lz4",
+            DBCompressionType::Lz4hc => "lz4hc",
+            DBCompressionType::Zstd => "zstd",
+            DBCompressionType::ZstdNotFinal => "zstd-not-final",
+            DBCompressionType::Disable => "disable",
+        }
+    }
+
+    fn type_of(name: &str) -> Option<DBCompressionType> {
+        Some(match name {
+            "no" => DBCompressionType::No,
+            "snappy" => DBCompressionType::Snappy,
+            "zlib" => DBCompressionType::Zlib,
+            "bzip2" => DBCompressionType::Bz2,
+            "lz4" => DBCompressionType::Lz4,
+            "lz4hc" => DBCompressionType::Lz4hc,
+            "zstd" => DBCompressionType::Zstd,
+            "zstd-not-final" => DBCompressionType::ZstdNotFinal,
+            "disable" => DBCompressionType::Disable,
+            _ => return None,
+        })
+    }
+
+    /// Parses one entry, accepting the plain `"zstd"` form as well as the parameterized
+    /// `"zstd:6"` and `"zstd(level=6)"` forms.
+    fn parse_entry(value: &str) -> Result<(DBCompressionType, Option<i32>), String> {
+        let value = value.trim();
+        let (name, level) = if let Some(open) = value.find('(') {
+            let name = &value[..open];
+            let args = value[open + 1..]
+                .strip_suffix(')')
+                .ok_or_else(|| format!("missing closing ')' in {:?}", value))?;
+            let level = args
+                .strip_prefix("level=")
+                .ok_or_else(|| format!("expect level=<n> in {:?}", value))?
+                .parse::<i32>()
+                .map_err(|e| format!("invalid level in {:?}: {}", value, e))?;
+            (name, Some(level))
+        } else if let Some((name, level)) = value.split_once(':') {
+            let level = level
+                .parse::<i32>()
+                .map_err(|e| format!("invalid level in {:?}: {}", value, e))?;
+            (name, Some(level))
+        } else {
+            (value, None)
+        };
+
+        let name = name.trim().to_lowercase();
+        let tp = type_of(&name).ok_or_else(|| format!("invalid compression type {:?}", value))?;
+        if level.is_some() && !accepts_level(tp) {
+            return Err(format!(
+                "{:?} does not support a compression level",
+                name_of(tp)
+            ));
+        }
+        Ok((tp, level))
+    }
+
+    pub fn serialize<S>(
+        ts: &[(DBCompressionType, Option<i32>); 7],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
         let mut s = serializer.serialize_seq(Some(ts.len()))?;
-        for t in ts {
-            let name = match *t {
-                DBCompressionType::No => "no",
-                DBCompressionType::Snappy => "snappy",
-                DBCompressionType::Zlib => "zlib",
-                DBCompressionType::Bz2 => "bzip2",
-                DBCompressionType::Lz4 => "lz4",
-                DBCompressionType::Lz4hc => "lz4hc",
-                DBCompressionType::Zstd => "zstd",
-                DBCompressionType::ZstdNotFinal => "zstd-not-final",
-                DBCompressionType::Disable => "disable",
-            };
-            s.serialize_element(name)?;
+        for (t, level) in ts {
+            match level {
+                Some(level) => s.serialize_element(&format!("{}:{}", name_of(*t), level))?,
+                None => s.serialize_element(name_of(*t))?,
+            }
         }
         s.end()
     }
 
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<[DBCompressionType; 7], D::Error>
+    pub fn deserialize<'de, D>(
+        deserializer: D,
+    ) -> Result<[(DBCompressionType, Option<i32>); 7], D::Error>
     where
         D: Deserializer<'de>,
     {
         struct SeqVisitor;
         impl<'de> Visitor<'de> for SeqVisitor {
-            type Value = [DBCompressionType; 7];
+            type Value = [(DBCompressionType, Option<i32>); 7];
 
             fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
                 write!(formatter, "a compression type vector")
             }
 
-            fn visit_seq<S>(self, mut seq: S) -> Result<[DBCompressionType; 7], S::Error>
+            fn visit_seq<S>(self, mut seq: S) -> Result<Self::Value, S::Error>
             where
                 S: SeqAccess<'de>,
             {
-                let mut seqs = [DBCompressionType::No; 7];
+                let mut seqs = [(DBCompressionType::No, None); 7];
                 let mut i = 0;
                 while let Some(value) = seq.next_element::<String>()? {
                     if i == 7 {
@@ -112,23 +232,8 @@ pub mod compression_type_level_serde {
                             &"only 7 compression types",
                         ));
                     }
-                    seqs[i] = match &*value.trim().to_lowercase() {
-                        "no" => DBCompressionType::No,
-                        "snappy" => DBCompressionType::Snappy,
-                        "zlib" => DBCompressionType::Zlib,
-                        "bzip2" => DBCompressionType::Bz2,
-                        "lz4" => DBCompressionType::Lz4,
-                        "lz4hc" => DBCompressionType::Lz4hc,
-                        "zstd" => DBCompressionType::Zstd,
-                        "zstd-not-final" => DBCompressionType::ZstdNotFinal,
-                        "disable" => DBCompressionType::Disable,
-                        _ => {
-                            return Err(S::Error::invalid_value(
-                                Unexpected::Str(&value),
-                                &"invalid compression type",
-                            ));
-                        }
-                    };
+                    seqs[i] = parse_entry(&value)
+                        .map_err(|e| S::Error::invalid_value(Unexpected::Str(&value), &&*e))?;
                     i += 1;
                 }
                 if i < 7 {
@@ -142,6 +247,47 @@ pub mod compression_type_level_serde {
     }
 }
 
+/// Configuration for per-level compression, optionally pairing each level's codec with a
+/// compression level (e.g. fast zstd at L0, high-ratio zstd at the bottom).
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct PerLevelCompressionConfig {
+    #[serde(with = "compression_type_level_serde")]
+    pub compression_per_level: [(DBCompressionType, Option<i32>); 7],
+}
+
+impl Default for PerLevelCompressionConfig {
+    fn default() -> PerLevelCompressionConfig {
+        PerLevelCompressionConfig {
+            compression_per_level: [(DBCompressionType::No, None); 7],
+        }
+    }
+}
+
+impl PerLevelCompressionConfig {
+    /// Applies this configuration to a column family's options: sets the per-level codec list,
+    /// and forwards any configured level into RocksDB's `CompressionOptions` for that level.
+    pub fn apply_to_cf_options(&self, cf_opts: &mut ColumnFamilyOptions) {
+        let types: Vec<DBCompressionType> = self
+            .compression_per_level
+            .iter()
+            .map(|(tp, _)| *tp)
+            .collect();
+        cf_opts.compression_per_level(&types);
+        for (level, (_, compression_level)) in self.compression_per_level.iter().enumerate() {
+            if let Some(compression_level) = compression_level {
+                // window_bits, strategy and max_dict_bytes keep RocksDB's defaults; only the
+                // level itself is operator-configurable here.
+                cf_opts.set_compression_options_for_level(
+                    level,
+                    CompressionOptions::new(-14, *compression_level, 0, 0),
+                );
+            }
+        }
+    }
+}
+
 pub mod compression_type_serde {
     use std::fmt;
 
@@ -209,6 +355,88 @@ pub mod compression_type_serde {
     }
 }
 
+/// Serde support for `wal_compression_type`. RocksDB streams the WAL through a single codec
+/// declared once up front, so only the codecs it supports there are accepted: `no` and `zstd`.
+pub mod wal_compression_type_serde {
+    use std::fmt;
+
+    use serde::de::{Error, Unexpected, Visitor};
+    use serde::{Deserializer, Serializer};
+
+    use rocksdb::DBCompressionType;
+
+    const ALLOWED: &str = "no, zstd";
+
+    pub fn serialize<S>(t: &DBCompressionType, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let name = match *t {
+            DBCompressionType::No => "no",
+            DBCompressionType::Zstd => "zstd",
+            _ => unreachable!("wal_compression_type only ever holds no or zstd"),
+        };
+        serializer.serialize_str(name)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<DBCompressionType, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct StrVistor;
+        impl<'de> Visitor<'de> for StrVistor {
+            type Value = DBCompressionType;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(formatter, "a WAL compression type")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<DBCompressionType, E>
+            where
+                E: Error,
+            {
+                match &*value.trim().to_lowercase() {
+                    "no" => Ok(DBCompressionType::No),
+                    "zstd" => Ok(DBCompressionType::Zstd),
+                    _ => Err(E::invalid_value(
+                        Unexpected::Str(value),
+                        &&*format!("wal_compression_type must be one of: {}", ALLOWED),
+                    )),
+                }
+            }
+        }
+
+        deserializer.deserialize_str(StrVistor)
+    }
+}
+
+/// Configuration for RocksDB's WAL compression, which writes a leading record declaring the
+/// codec for all subsequent records.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct WalCompressionConfig {
+    #[serde(with = "wal_compression_type_serde")]
+    pub wal_compression_type: DBCompressionType,
+}
+
+impl Default for WalCompressionConfig {
+    fn default() -> WalCompressionConfig {
+        // `No` keeps existing deployments behaving exactly as before until an operator opts in.
+        WalCompressionConfig {
+            wal_compression_type: DBCompressionType::No,
+        }
+    }
+}
+
+impl WalCompressionConfig {
+    /// Applies this configuration to a `DBOptions` builder; setting anything other than `No`
+    /// turns on WAL compression with the configured codec.
+    pub fn apply_to_db_options(&self, db_opts: &mut DBOptions) {
+        db_opts.set_wal_compression_type(self.wal_compression_type);
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum BlobRunMode {
@@ -447,7 +675,7 @@ mod tests {
         #[derive(Serialize, Deserialize)]
         struct CompressionTypeHolder {
             #[serde(with = "compression_type_level_serde")]
-            tp: [DBCompressionType; 7],
+            tp: [(DBCompressionType, Option<i32>); 7],
         }
 
         let all_tp = vec![
@@ -462,10 +690,10 @@ mod tests {
             (DBCompressionType::Disable, "disable"),
         ];
         for i in 0..all_tp.len() - 7 {
-            let mut src = [DBCompressionType::No; 7];
+            let mut src = [(DBCompressionType::No, None); 7];
             let mut exp = ["no"; 7];
             for (i, &t) in all_tp[i..i + 7].iter().enumerate() {
-                src[i] = t.0;
+                src[i] = (t.0, None);
                 exp[i] = t.1;
             }
             let holder = CompressionTypeHolder { tp: src };
@@ -496,4 +724,147 @@ mod tests {
             .is_err()
         );
     }
+
+    #[test]
+    fn test_parse_compression_type_level() {
+        #[derive(Serialize, Deserialize)]
+        struct CompressionTypeLevelHolder {
+            #[serde(with = "compression_type_level_serde")]
+            tp: [(DBCompressionType, Option<i32>); 7],
+        }
+
+        let src = [
+            (DBCompressionType::No, None),
+            (DBCompressionType::Zstd, Some(6)),
+            (DBCompressionType::Zstd, Some(6)),
+            (DBCompressionType::Zstd, Some(6)),
+            (DBCompressionType::Zstd, Some(6)),
+            (DBCompressionType::Zstd, None),
+            (DBCompressionType::Zstd, Some(19)),
+        ];
+        let holder = CompressionTypeLevelHolder { tp: src };
+        let res_str = toml::to_string(&holder).unwrap();
+        assert_eq!(
+            res_str,
+            "tp = [\"no\", \"zstd:6\", \"zstd:6\", \"zstd:6\", \"zstd:6\", \"zstd\", \"zstd:19\"]\n"
+        );
+        let h: CompressionTypeLevelHolder = toml::from_str(&res_str).unwrap();
+        assert_eq!(h.tp, holder.tp);
+
+        // the parameterized form is also accepted on read.
+        let h: CompressionTypeLevelHolder = toml::from_str(
+            r#"tp = ["no", "zstd(level=6)", "zstd", "zstd", "zstd", "zstd", "zstd"]"#,
+        )
+        .unwrap();
+        assert_eq!(h.tp[1], (DBCompressionType::Zstd, Some(6)));
+
+        // codecs that ignore levels must reject them.
+        assert!(toml::from_str::<CompressionTypeLevelHolder>(
+            r#"tp = ["snappy:6", "no", "no", "no", "no", "no", "no"]"#
+        )
+        .is_err());
+        assert!(toml::from_str::<CompressionTypeLevelHolder>(
+            r#"tp = ["no:1", "no", "no", "no", "no", "no", "no"]"#
+        )
+        .is_err());
+
+        // "disable" is still accepted on deserialize.
+        let h: CompressionTypeLevelHolder = toml::from_str(
+            r#"tp = ["disable", "no", "no", "no", "no", "no", "no"]"#,
+        )
+        .unwrap();
+        assert_eq!(h.tp[0], (DBCompressionType::Disable, None));
+
+        // length is wrong.
+        assert!(toml::from_str::<CompressionTypeLevelHolder>("tp = [\"no\"]").is_err());
+    }
+
+    #[test]
+    fn test_parse_wal_compression_type() {
+        #[derive(Serialize, Deserialize)]
+        struct WalCompressionTypeHolder {
+            #[serde(with = "wal_compression_type_serde")]
+            tp: DBCompressionType,
+        }
+
+        for (tp, name) in [(DBCompressionType::No, "no"), (DBCompressionType::Zstd, "zstd")] {
+            let holder = WalCompressionTypeHolder { tp };
+            let res_str = toml::to_string(&holder).unwrap();
+            assert_eq!(res_str, format!("tp = \"{}\"\n", name));
+            let h: WalCompressionTypeHolder = toml::from_str(&res_str).unwrap();
+            assert_eq!(h.tp, holder.tp);
+        }
+
+        // only `no` and `zstd` are valid WAL codecs.
+        assert!(toml::from_str::<WalCompressionTypeHolder>("tp = \"snappy\"").is_err());
+        assert!(toml::from_str::<WalCompressionTypeHolder>("tp = \"lz4\"").is_err());
+    }
+
+    #[test]
+    fn test_wal_compression_config_default() {
+        let cfg = WalCompressionConfig::default();
+        assert_eq!(cfg.wal_compression_type, DBCompressionType::No);
+
+        let toml_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(toml_str, "wal-compression-type = \"no\"\n");
+        let parsed: WalCompressionConfig = toml::from_str(&toml_str).unwrap();
+        assert_eq!(parsed, cfg);
+    }
+
+    #[test]
+    fn test_compression_config_threshold() {
+        let cfg = CompressionConfig::default();
+        assert_eq!(cfg.compression_threshold, ReadableSize(256));
+
+        assert_eq!(
+            cfg.effective_compression_type(100, DBCompressionType::Zstd),
+            DBCompressionType::No
+        );
+        assert_eq!(
+            cfg.effective_compression_type(256, DBCompressionType::Zstd),
+            DBCompressionType::Zstd
+        );
+        assert_eq!(
+            cfg.effective_compression_type(1024, DBCompressionType::Zstd),
+            DBCompressionType::Zstd
+        );
+    }
+
+    #[test]
+    fn test_per_level_compression_config_applies_to_cf_options() {
+        let mut cfg = PerLevelCompressionConfig::default();
+        cfg.compression_per_level[0] = (DBCompressionType::Zstd, Some(1));
+        cfg.compression_per_level[6] = (DBCompressionType::Zstd, Some(19));
+
+        let mut cf_opts = ColumnFamilyOptions::new();
+        cfg.apply_to_cf_options(&mut cf_opts);
+    }
+
+    #[test]
+    fn test_wal_compression_config_applies_to_db_options() {
+        let mut db_opts = DBOptions::new();
+        WalCompressionConfig::default().apply_to_db_options(&mut db_opts);
+
+        let mut cfg = WalCompressionConfig::default();
+        cfg.wal_compression_type = DBCompressionType::Zstd;
+        cfg.apply_to_db_options(&mut db_opts);
+    }
+
+    #[test]
+    fn test_compression_type_tag_roundtrip() {
+        let all = [
+            CompressionType::No,
+            CompressionType::Snappy,
+            CompressionType::Zlib,
+            CompressionType::Bz2,
+            CompressionType::Lz4,
+            CompressionType::Lz4hc,
+            CompressionType::Zstd,
+            CompressionType::ZstdNotFinal,
+        ];
+        for tp in all {
+            assert_eq!(CompressionType::from_u8(tp as u8), Some(tp));
+        }
+        assert_eq!(CompressionType::from_u8(255), None);
+    }
 }
@@ -0,0 +1,162 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Parallel block compression for large byte streams (backup/snapshot export).
+//!
+//! The input is split into fixed-size, independently compressed blocks so that a worker pool
+//! can compress them concurrently; the output is framed so a reader can decompress blocks
+//! independently and in order, without needing to hold the whole stream in memory.
+
+use std::io::{self, Read, Write};
+use std::sync::mpsc;
+
+use tikv_util::sys::{memory_usage_reaches_high_water, SysQuota};
+
+use crate::compressor;
+use crate::config::CompressionType;
+
+/// Block size used to split the input, matching the granularity crabz/gzp use for parallel
+/// gzip: large enough to amortize compressor setup cost, small enough to parallelize well.
+pub const BLOCK_SIZE: usize = 64 * 1024;
+
+/// Bounds the number of blocks queued for compression (sent but not yet taken by a worker), so
+/// the reader can't race arbitrarily far ahead of the workers. Compressed blocks are not
+/// bounded by this: they are all held until the last block is done, then written out.
+const MAX_PENDING_BLOCKS: usize = 32;
+
+/// Picks a worker count from the cgroup-aware CPU quota rather than `num_cpus`, so the pool
+/// doesn't oversubscribe when TiKV is itself confined to a fraction of the host's cores.
+pub fn default_worker_count() -> usize {
+    std::cmp::max(1, SysQuota::cpu_cores_quota() as usize)
+}
+
+/// Compresses `input` using a pool of `workers` threads, each block independently compressed
+/// with `tp`. Blocks are written to `output` in order, framed as `[codec: u8][len: u32][data]`.
+pub fn compress(input: &[u8], tp: CompressionType, workers: usize, output: &mut impl Write) -> io::Result<()> {
+    let workers = workers.max(1);
+    let blocks: Vec<&[u8]> = input.chunks(BLOCK_SIZE).collect();
+    if blocks.is_empty() {
+        return Ok(());
+    }
+
+    // A bounded channel, capped by `MAX_PENDING_BLOCKS`, limits how many read-but-uncompressed
+    // blocks can queue up; the loop below shrinks that budget further under memory pressure.
+    let queue_cap = MAX_PENDING_BLOCKS.min(blocks.len());
+    let (job_tx, job_rx) = crossbeam::channel::bounded::<(usize, &[u8])>(queue_cap);
+    let (res_tx, res_rx) = mpsc::channel::<(usize, u8, Vec<u8>)>();
+
+    // `tp` is constant for the whole call, so the registry lock is taken once here rather than
+    // once per block inside the hot loop every worker thread runs.
+    let compressor = compressor::resolve(tp);
+
+    crossbeam::scope(|scope| {
+        for _ in 0..workers {
+            let job_rx = job_rx.clone();
+            let res_tx = res_tx.clone();
+            let compressor = compressor.clone();
+            scope.spawn(move |_| {
+                while let Ok((idx, block)) = job_rx.recv() {
+                    let data = compressor.encode(block);
+                    if res_tx.send((idx, compressor.id(), data)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(res_tx);
+
+        for (idx, block) in blocks.iter().enumerate() {
+            // Under memory pressure, shrink the allowed in-flight budget down to a single
+            // block instead of the normal `queue_cap`, so the reader stalls on `job_tx.len()`
+            // until workers drain the queue or usage drops, rather than racing ahead on an
+            // already-full channel.
+            loop {
+                let mut usage = 0;
+                let budget = if memory_usage_reaches_high_water(&mut usage) {
+                    1
+                } else {
+                    queue_cap
+                };
+                if job_tx.len() < budget {
+                    break;
+                }
+                std::thread::yield_now();
+            }
+            job_tx.send((idx, block)).expect("workers outlive the job queue");
+        }
+        drop(job_tx);
+    })
+    .expect("compression worker panicked");
+
+    let mut results: Vec<Option<(u8, Vec<u8>)>> = vec![None; blocks.len()];
+    for (idx, codec, data) in res_rx {
+        results[idx] = Some((codec, data));
+    }
+
+    for result in results {
+        let (codec, data) = result.expect("every block is compressed exactly once");
+        output.write_all(&[codec])?;
+        output.write_all(&(data.len() as u32).to_le_bytes())?;
+        output.write_all(&data)?;
+    }
+    Ok(())
+}
+
+/// Reads a frame produced by [`compress`] and reassembles the original stream.
+pub fn decompress(input: &mut impl Read, output: &mut impl Write) -> io::Result<()> {
+    loop {
+        let mut header = [0u8; 5];
+        match input.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let codec = header[0];
+        let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        let mut data = vec![0u8; len];
+        input.read_exact(&mut data)?;
+        let block = decompress_block(&data, codec)?;
+        output.write_all(&block)?;
+    }
+}
+
+fn decompress_block(data: &[u8], codec: u8) -> io::Result<Vec<u8>> {
+    let compressor = compressor::get_compressor(codec)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("unknown codec tag {}", codec)))?;
+    compressor.decode(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_decompress_roundtrip() {
+        let input: Vec<u8> = (0..BLOCK_SIZE * 3 + 17).map(|i| (i % 251) as u8).collect();
+        for tp in [CompressionType::No, CompressionType::Snappy, CompressionType::Lz4, CompressionType::Zstd] {
+            let mut framed = Vec::new();
+            compress(&input, tp, default_worker_count(), &mut framed).unwrap();
+
+            let mut restored = Vec::new();
+            decompress(&mut framed.as_slice(), &mut restored).unwrap();
+            assert_eq!(restored, input, "roundtrip mismatch for {:?}", tp);
+        }
+    }
+
+    #[test]
+    fn test_default_worker_count_is_at_least_one() {
+        assert!(default_worker_count() >= 1);
+    }
+
+    #[test]
+    fn test_compress_throttles_under_memory_pressure() {
+        fail::cfg("memory_usage_reaches_high_water", "return(true)").unwrap();
+        let input: Vec<u8> = (0..BLOCK_SIZE * 4).map(|i| (i % 251) as u8).collect();
+        let mut framed = Vec::new();
+        compress(&input, CompressionType::Zstd, default_worker_count(), &mut framed).unwrap();
+        fail::remove("memory_usage_reaches_high_water");
+
+        let mut restored = Vec::new();
+        decompress(&mut framed.as_slice(), &mut restored).unwrap();
+        assert_eq!(restored, input);
+    }
+}